@@ -14,6 +14,7 @@ use ::std::collections::HashMap;
 use ::std::collections::HashSet;
 use ::std::env::args_os;
 use ::std::env::vars_os;
+use ::std::ffi::OsStr;
 use ::std::ffi::OsString;
 use ::std::fs::File;
 use ::std::io::BufReader;
@@ -22,6 +23,7 @@ use ::std::io::prelude::*;
 #[cfg(unix)] use ::std::os::unix::ffi::OsStrExt;
 #[cfg(unix)] use ::std::os::unix::process::CommandExt;
 use ::std::path::Path;
+use ::std::path::PathBuf;
 use ::std::process::Command;
 use ::std::process::Stdio;
 
@@ -63,13 +65,25 @@ fn osStringFromRawBytesWithoutADelimiter(mut environmentVariableRawBytes: Vec<u8
 	OsString::from_vec(environmentVariableRawBytes)
 }
 
+/// Unlike `osStringFromRawBytesWithoutADelimiter`, this does not append a trailing ASCII NUL; use it for values that end up being passed to `exec()`/`Command`, which reject embedded NULs
+fn osStringFromRawBytes(environmentVariableRawBytes: Vec<u8>) -> OsString
+{
+	OsString::from_vec(environmentVariableRawBytes)
+}
+
 impl EnvironmentVariable
 {
 	pub fn fromRawBytesWithoutADelimiter(environmentVariableRawBytes: Vec<u8>) -> Self
 	{
 		EnvironmentVariable(osStringFromRawBytesWithoutADelimiter(environmentVariableRawBytes))
 	}
-	
+
+	/// As `fromRawBytesWithoutADelimiter`, but without the spurious trailing ASCII NUL; use this for names that must compare equal to (or be inserted directly into) a real process environment
+	pub fn fromRawBytes(environmentVariableRawBytes: Vec<u8>) -> Self
+	{
+		EnvironmentVariable(osStringFromRawBytes(environmentVariableRawBytes))
+	}
+
 	pub fn to_os_string(self) -> OsString
 	{
 		self.0
@@ -157,16 +171,138 @@ impl<'a> WhiteList<'a>
 		self.0.contains(environmentVariableName)
 	}
 	
-	pub fn filterEnvironment(&self) -> HashMap<OsString, OsString>
+	/// Also returns the incoming variables that were dropped and why; used to support `--print-dropped`
+	pub fn filterEnvironmentCollectingDropped(&self) -> (HashMap<OsString, OsString>, Vec<(OsString, DropReason)>)
 	{
 		let blackList = self.1;
-		vars_os()
-		.filter(|&(ref environmentVariableName, _)| blackList.isNotBlackListed(&EnvironmentVariable(environmentVariableName.to_os_string())))
-		.filter(|&(ref environmentVariableName, _)| self.isWhiteListed(&EnvironmentVariable(environmentVariableName.to_os_string())))
-		.collect()
+		let mut kept = HashMap::new();
+		let mut dropped = Vec::new();
+
+		for (environmentVariableNameRaw, value) in vars_os()
+		{
+			let environmentVariableName = EnvironmentVariable(environmentVariableNameRaw.to_os_string());
+			if blackList.isBlackListed(&environmentVariableName)
+			{
+				dropped.push((environmentVariableNameRaw, DropReason::BlackListed));
+			}
+			else if !self.isWhiteListed(&environmentVariableName)
+			{
+				dropped.push((environmentVariableNameRaw, DropReason::NotWhiteListed));
+			}
+			else
+			{
+				kept.insert(environmentVariableNameRaw, value);
+			}
+		}
+
+		(kept, dropped)
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason
+{
+	BlackListed,
+	NotWhiteListed,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathListSanitizer(HashSet<EnvironmentVariable>);
+
+impl PathListSanitizer
+{
+	pub fn new(defaultPathListSanitizer: Vec<EnvironmentVariable>) -> Self
+	{
+		let mut pathListSanitizer = PathListSanitizer(HashSet::with_capacity(defaultPathListSanitizer.len() + 8));
+		for environmentVariableName in defaultPathListSanitizer
+		{
+			pathListSanitizer.0.insert(environmentVariableName);
+		}
+		pathListSanitizer
+	}
+
+	pub fn addToFromFile(&mut self, filePath: &Path)
+	{
+		// Deliberately does not use addEnvironmentVariablesFromLinesListedInFile(): that embeds a trailing ASCII NUL in the parsed name, which would never match the NUL-free names isPathListed() is queried with
+		for environmentVariableNameRawBytes in rawLinesListedInFile("Paths", filePath)
+		{
+			self.0.insert(EnvironmentVariable::fromRawBytes(environmentVariableNameRawBytes));
+		}
+	}
+
+	pub fn isPathListed(&self, environmentVariableName: &EnvironmentVariable) -> bool
+	{
+		self.0.contains(environmentVariableName)
+	}
+
+	pub fn sanitizeEnvironment(&self, mut environment: HashMap<OsString, OsString>) -> HashMap<OsString, OsString>
+	{
+		for (environmentVariableName, value) in environment.iter_mut()
+		{
+			if self.isPathListed(&EnvironmentVariable(environmentVariableName.to_os_string()))
+			{
+				*value = sanitizePathListValue(value);
+			}
+		}
+		environment
+	}
+}
+
+fn sanitizePathListValue(value: &OsString) -> OsString
+{
+	use ::std::env::split_paths;
+	use ::std::env::join_paths;
+
+	let sanitizedComponents: Vec<PathBuf> = split_paths(value)
+	.filter(|component| !component.as_os_str().is_empty())
+	.filter(|component| component.is_absolute())
+	.filter(|component| isUsableDirectory(component))
+	.collect();
+
+	// join_paths() fails if any surviving component itself contains the path separator byte; rebuild one component at a time, dropping the offender
+	match join_paths(sanitizedComponents.iter())
+	{
+		Ok(joined) => joined,
+		Err(_) =>
+		{
+			let mut safeComponents: Vec<PathBuf> = Vec::with_capacity(sanitizedComponents.len());
+			for component in sanitizedComponents
+			{
+				let mut candidate = safeComponents.clone();
+				candidate.push(component.clone());
+				if join_paths(candidate.iter()).is_ok()
+				{
+					safeComponents.push(component);
+				}
+				else
+				{
+					warn!("Path list component '{:?}' contains the path list separator and has been dropped", component);
+				}
+			}
+			join_paths(safeComponents.iter()).unwrap_or_default()
+		}
+	}
+}
+
+#[cfg(unix)]
+fn isUsableDirectory(path: &Path) -> bool
+{
+	use ::std::fs::metadata;
+	use ::std::os::unix::fs::MetadataExt;
+
+	match metadata(path)
+	{
+		Err(_) => false,
+		Ok(metadata) => metadata.is_dir() && metadata.mode() & 0o022 == 0,
+	}
+}
+
+#[cfg(not(unix))]
+fn isUsableDirectory(path: &Path) -> bool
+{
+	path.is_dir()
+}
+
 #[derive(Debug, Clone)]
 pub struct SettingsList(HashMap<EnvironmentVariable, OsString>);
 
@@ -186,24 +322,130 @@ impl SettingsList
 		environment
 	}
 	
-	pub fn addToFromFile(&mut self, filePath: &Path)
+	/// Lines are `NAME \t OPERATOR \t VALUE`, where OPERATOR is `=` (set), `+` (append, using the OS path list separator) or `^` (prepend, using the OS path list separator).
+	/// VALUE may reference `${OTHER}`, which is expanded against the settings accumulated so far (the defaults passed to `new()` plus earlier lines of this file), falling back to `environment` (the already-filtered process environment)
+	pub fn addToFromFile(&mut self, filePath: &Path, environment: &HashMap<OsString, OsString>)
 	{
 		addFromLinesListedInFile("Settings", filePath, |environmentVariableRawBytesExcludingDelimiter, fileKind, filePath, line|
 		{
 			const Tab: u8 = b'\t';
-			match memchr::memchr(Tab, environmentVariableRawBytesExcludingDelimiter.as_slice())
+			let rawLine = environmentVariableRawBytesExcludingDelimiter.as_slice();
+
+			let nameEndIndex = match memchr::memchr(Tab, rawLine)
 			{
 				None => fatalExit!("There is no tab delimiter in {} list file '{:?}' at line '{}' (all offsets are zero-based)", fileKind, filePath, line),
-				Some(index) =>
+				Some(index) => index,
+			};
+
+			let afterName = &rawLine[nameEndIndex + 1..];
+			let operatorEndIndex = match memchr::memchr(Tab, afterName)
+			{
+				None => fatalExit!("There is no second tab delimiter (between operator and value) in {} list file '{:?}' at line '{}' (all offsets are zero-based)", fileKind, filePath, line),
+				Some(index) => index,
+			};
+
+			let name = EnvironmentVariable::fromRawBytes(Vec::from(&rawLine[0..nameEndIndex]));
+			let operator = &afterName[0..operatorEndIndex];
+			let rawValue = &afterName[operatorEndIndex + 1..];
+
+			let value = expandTemplateReferences(rawValue, &self.0, environment, fileKind, filePath, line);
+
+			match operator
+			{
+				b"=" => { self.0.insert(name, value); },
+				b"+" => self.appendValue(name, value, environment),
+				b"^" => self.prependValue(name, value, environment),
+				_ => fatalExit!("Unknown operator '{:?}' in {} list file '{:?}' at line '{}' (all offsets are zero-based)", String::from_utf8_lossy(operator), fileKind, filePath, line),
+			}
+		})
+	}
+
+	/// The prior value to combine with comes from settings already accumulated in this `SettingsList` if present, otherwise the already-filtered process environment, so `PATH + /extra/bin` builds on the live `PATH` rather than discarding it
+	fn appendValue(&mut self, name: EnvironmentVariable, value: OsString, environment: &HashMap<OsString, OsString>)
+	{
+		let combined = match self.0.remove(&name).or_else(|| environment.get(&name.0).cloned())
+		{
+			Some(existing) => joinWithPathListSeparator(existing, value),
+			None => value,
+		};
+		self.0.insert(name, combined);
+	}
+
+	fn prependValue(&mut self, name: EnvironmentVariable, value: OsString, environment: &HashMap<OsString, OsString>)
+	{
+		let combined = match self.0.remove(&name).or_else(|| environment.get(&name.0).cloned())
+		{
+			Some(existing) => joinWithPathListSeparator(value, existing),
+			None => value,
+		};
+		self.0.insert(name, combined);
+	}
+}
+
+/// Expands `${OTHER}` references in `rawValue`, checking settings accumulated so far first and then the already-filtered process environment
+fn expandTemplateReferences(rawValue: &[u8], settingsSoFar: &HashMap<EnvironmentVariable, OsString>, environment: &HashMap<OsString, OsString>, fileKind: &'static str, filePath: &Path, line: u64) -> OsString
+{
+	const DollarBrace: &[u8] = b"${";
+	const CloseBrace: u8 = b'}';
+
+	let mut expanded: Vec<u8> = Vec::with_capacity(rawValue.len());
+	let mut index = 0;
+	while index < rawValue.len()
+	{
+		if rawValue[index..].starts_with(DollarBrace)
+		{
+			let nameStartIndex = index + DollarBrace.len();
+			match memchr::memchr(CloseBrace, &rawValue[nameStartIndex..])
+			{
+				None => fatalExit!("Unterminated '${{...}}' reference in {} list file '{:?}' at line '{}' (all offsets are zero-based)", fileKind, filePath, line),
+				Some(relativeCloseIndex) =>
 				{
-					let name = EnvironmentVariable::fromRawBytesWithoutADelimiter(Vec::from(&environmentVariableRawBytesExcludingDelimiter[0..index]));
-					let value = osStringFromRawBytesWithoutADelimiter(Vec::from(&environmentVariableRawBytesExcludingDelimiter[index + 1..]));
-					
-					self.0.insert(name, value);
+					let nameEndIndex = nameStartIndex + relativeCloseIndex;
+					let referencedNameRawBytes = Vec::from(&rawValue[nameStartIndex..nameEndIndex]);
+
+					let referencedSetting = EnvironmentVariable::fromRawBytes(referencedNameRawBytes.clone());
+					if let Some(value) = settingsSoFar.get(&referencedSetting)
+					{
+						expanded.extend_from_slice(value.as_bytes());
+					}
+					else if let Some(value) = environment.get(&OsString::from_vec(referencedNameRawBytes))
+					{
+						expanded.extend_from_slice(value.as_bytes());
+					}
+
+					index = nameEndIndex + 1;
 				}
 			}
-		})
+		}
+		else
+		{
+			expanded.push(rawValue[index]);
+			index += 1;
+		}
 	}
+
+	osStringFromRawBytes(expanded)
+}
+
+#[cfg(unix)]
+fn joinWithPathListSeparator(first: OsString, second: OsString) -> OsString
+{
+	const PathListSeparator: u8 = b':';
+
+	let mut bytes = Vec::with_capacity(first.as_os_str().as_bytes().len() + 1 + second.as_os_str().as_bytes().len());
+	bytes.extend_from_slice(first.as_os_str().as_bytes());
+	bytes.push(PathListSeparator);
+	bytes.extend_from_slice(second.as_os_str().as_bytes());
+	OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn joinWithPathListSeparator(first: OsString, second: OsString) -> OsString
+{
+	let mut combined = first;
+	combined.push(";");
+	combined.push(second);
+	combined
 }
 
 /// This logic only works if there are not any LineFeed characters EMBEDDED within a line
@@ -248,41 +490,93 @@ fn addEnvironmentVariablesFromLinesListedInFile<A: FnMut(EnvironmentVariable, &P
 	});
 }
 
-pub fn parseCommandLineArguments() -> (OsString, Vec<OsString>)
+/// Reads a `profiles` (or similarly simple, one-token-per-line) list file as raw, unparsed lines; used where the caller's own code (eg a profile name registry) knows how to interpret each line
+pub fn rawLinesListedInFile(fileKind: &'static str, filePath: &Path) -> Vec<Vec<u8>>
+{
+	let mut lines = Vec::new();
+	addFromLinesListedInFile(fileKind, filePath, |rawBytes, _, _, _|
+	{
+		lines.push(rawBytes);
+	});
+	lines
+}
+
+/// Options recognized before the program name; mirrors the small, hand-rolled getopts style used by rustc's compiletest rather than pulling in a full options-parsing crate
+#[derive(Debug, Clone, Default)]
+pub struct CommandLineOptions
+{
+	pub dryRun: bool,
+	pub printDropped: bool,
+	pub settingsDirectory: Option<PathBuf>,
+}
+
+pub fn parseCommandLineArguments() -> (CommandLineOptions, OsString, Vec<OsString>)
 {
 	// This logic is designed to work with sha-bang paths, eg
-	// /usr/bin/environment-sanity program-to-invoke <any> <other> <arguments>
+	// /usr/bin/environment-sanity [options] program-to-invoke <any> <other> <arguments>
 	// sha-bang paths as used as a command interpreter may not support <any> <other> <arguments>
-	
+
 	// Skip the first argument, which is 'us'
 	let mut inputArguments = args_os().skip(1);
-	
-	// Take the second argument, which is the program to invoke
-	let programName = match inputArguments.next()
+
+	let mut options = CommandLineOptions::default();
+
+	// Parse options until the first non-option token, which (along with everything after it) is the program name plus its arguments
+	let programName = loop
 	{
-		None => fatalExit!("Please provide at least one argument, which is the program to {}", "invoke"),
-		Some(programName) =>
+		let argument = match inputArguments.next()
 		{
-			if programName.is_empty()
+			None => fatalExit!("Please provide at least one argument, which is the program to {}", "invoke"),
+			Some(argument) => argument,
+		};
+
+		if argument.as_os_str() == OsStr::new("--")
+		{
+			break match inputArguments.next()
 			{
-				fatalExit!("{}", "First argument can not be empty");
-			}
-			
-			const Slash: u8 = b'/';
-			if memchr::memchr(Slash, programName.as_os_str().as_bytes()).is_some()
+				None => fatalExit!("Please provide at least one argument, which is the program to {}", "invoke"),
+				Some(programName) => programName,
+			};
+		}
+		else if argument.as_os_str() == OsStr::new("--dry-run") || argument.as_os_str() == OsStr::new("-n")
+		{
+			options.dryRun = true;
+		}
+		else if argument.as_os_str() == OsStr::new("--print-dropped")
+		{
+			options.printDropped = true;
+		}
+		else if argument.as_os_str() == OsStr::new("--settings-dir")
+		{
+			options.settingsDirectory = match inputArguments.next()
 			{
-				fatalExit!("First argument is the program name to invoke. It must be a file, not a path like '{:?}'", programName);
-			}
-			
-			programName
+				None => fatalExit!("{}", "--settings-dir requires a directory argument"),
+				Some(directory) => Some(PathBuf::from(directory)),
+			};
+		}
+		else
+		{
+			break argument;
 		}
 	};
-	
+
+	if programName.is_empty()
+	{
+		fatalExit!("{}", "Program name argument can not be empty");
+	}
+
+	const Slash: u8 = b'/';
+	if memchr::memchr(Slash, programName.as_os_str().as_bytes()).is_some()
+	{
+		fatalExit!("The program name to invoke must be a file, not a path like '{:?}'", programName);
+	}
+
 	let outputArguments = inputArguments.collect();
-	
-	(programName, outputArguments)
+
+	(options, programName, outputArguments)
 }
 
+#[cfg(unix)]
 pub fn execute(programName: OsString, arguments: Vec<OsString>, filteredEnvironment: HashMap<OsString, OsString>) -> !
 {
 	let error = Command::new(&programName)
@@ -292,6 +586,31 @@ pub fn execute(programName: OsString, arguments: Vec<OsString>, filteredEnvironm
 	.args(&arguments)
 	.env_clear().envs(&filteredEnvironment)
 	.exec();
-	
+
 	fatalExit!("Could not execute '{:?}' because '{:?}'", programName, error);
 }
+
+// There is no exec() replacement outside of unix; fall back to spawning the child, waiting for it and then mirroring its exit code
+#[cfg(not(unix))]
+pub fn execute(programName: OsString, arguments: Vec<OsString>, filteredEnvironment: HashMap<OsString, OsString>) -> !
+{
+	let status = Command::new(&programName)
+	.stdin(Stdio::inherit())
+	.stdout(Stdio::inherit())
+	.stderr(Stdio::inherit())
+	.args(&arguments)
+	.env_clear().envs(&filteredEnvironment)
+	.status();
+
+	match status
+	{
+		Err(error) => fatalExit!("Could not execute '{:?}' because '{:?}'", programName, error),
+		Ok(exitStatus) =>
+		{
+			// This path is cfg(not(unix)), so there is no signal concept to propagate here; an absent exit code (which on unix would mean killed-by-signal) just becomes a plain failure
+			let exitCode = exitStatus.code().unwrap_or(1);
+
+			::std::process::exit(exitCode);
+		},
+	}
+}
@@ -15,38 +15,124 @@ use ::std::env::remove_var;
 use ::std::env::var_os;
 use ::std::ffi::OsStr;
 use ::std::ffi::OsString;
+use ::std::io::Write;
+use ::std::io::stdout;
+use ::std::path::Path;
 use ::std::path::PathBuf;
 
 
 pub fn main()
 {
 	homeFolderIgnoringValueOfHomeVariable();
-	
-	let (programName, outputArguments) = parseCommandLineArguments();
-	
-	let mut blackList = BlackList::new(defaultBlackList());
-	if let Some(filePath) = settingsFor(&programName, "Black")
+
+	let (options, programName, outputArguments) = parseCommandLineArguments();
+
+	let settingsDirectory = options.settingsDirectory.clone().unwrap_or_else(|| homeFolderIgnoringValueOfHomeVariable().join(".environment-sanity/settings"));
+
+	let profiles = profilesFor(&settingsDirectory, &programName);
+
+	let mut blackListDefaults = defaultBlackList();
+	let mut whiteListDefaults = defaultWhiteList();
+	let mut settingsDefaults = defaultSettings(&programName);
+	for profile in &profiles
+	{
+		blackListDefaults.extend(profile.blackList());
+		whiteListDefaults.extend(profile.whiteList());
+		for name in profile.settingsToRemove()
+		{
+			settingsDefaults.remove(&name);
+		}
+		for (name, value) in profile.settings()
+		{
+			settingsDefaults.insert(name, value);
+		}
+	}
+
+	let mut blackList = BlackList::new(blackListDefaults);
+	if let Some(filePath) = settingsFor(&settingsDirectory, &programName, "Black")
 	{
 		blackList.addToFromFile(&filePath)
 	}
-	
-	let mut whiteList = WhiteList::new(&blackList, defaultWhiteList());
-	if let Some(filePath) = settingsFor(&programName, "White")
+
+	let mut whiteList = WhiteList::new(&blackList, whiteListDefaults);
+	if let Some(filePath) = settingsFor(&settingsDirectory, &programName, "White")
 	{
 		whiteList.addToFromFile(&filePath)
 	}
-	
-	let mut settingsList = SettingsList::new(defaultSettings(&programName));
-	if let Some(filePath) = settingsFor(&programName, "Settings")
+
+	let mut pathListSanitizer = PathListSanitizer::new(defaultPathListSanitizer());
+	if let Some(filePath) = settingsFor(&settingsDirectory, &programName, "Paths")
 	{
-		settingsList.addToFromFile(&filePath)
+		pathListSanitizer.addToFromFile(&filePath)
 	}
-	
-	let filteredEnvironment = whiteList.filterEnvironment();
-	let environment = settingsList.addSettingsToEnvironment(filteredEnvironment);
+
+	let mut settingsList = SettingsList::new(settingsDefaults);
+
+	let (filteredEnvironment, droppedEnvironmentVariables) = whiteList.filterEnvironmentCollectingDropped();
+
+	if options.printDropped
+	{
+		printDropped(&droppedEnvironmentVariables);
+	}
+
+	let sanitizedEnvironment = pathListSanitizer.sanitizeEnvironment(filteredEnvironment);
+
+	if let Some(filePath) = settingsFor(&settingsDirectory, &programName, "Settings")
+	{
+		settingsList.addToFromFile(&filePath, &sanitizedEnvironment)
+	}
+
+	// Settings-file appends/prepends/sets can reintroduce unsanitized path-list values (eg `PATH ^ ${HOME}/.local/bin`), so sanitize again after merging them in
+	let environment = pathListSanitizer.sanitizeEnvironment(settingsList.addSettingsToEnvironment(sanitizedEnvironment));
+
+	if options.dryRun
+	{
+		printEnvironment(&environment);
+		return;
+	}
+
 	execute(programName, outputArguments, environment);
 }
 
+fn printEnvironment(environment: &HashMap<OsString, OsString>)
+{
+	let stdoutHandle = stdout();
+	let mut lock = stdoutHandle.lock();
+	for (environmentVariableName, value) in environment
+	{
+		writeNameValueLine(&mut lock, environmentVariableName, value);
+	}
+}
+
+fn printDropped(droppedEnvironmentVariables: &[(OsString, DropReason)])
+{
+	for &(ref environmentVariableName, reason) in droppedEnvironmentVariables
+	{
+		let reasonDescription = match reason
+		{
+			DropReason::BlackListed => "blacklisted",
+			DropReason::NotWhiteListed => "not whitelisted",
+		};
+		eprintln!("{}: {}", reasonDescription, environmentVariableName.to_string_lossy());
+	}
+}
+
+#[cfg(unix)]
+fn writeNameValueLine(handle: &mut ::std::io::StdoutLock, name: &OsStr, value: &OsStr)
+{
+	use ::std::os::unix::ffi::OsStrExt;
+	handle.write_all(name.as_bytes()).expect("Could not write to stdout");
+	handle.write_all(b"=").expect("Could not write to stdout");
+	handle.write_all(value.as_bytes()).expect("Could not write to stdout");
+	handle.write_all(b"\n").expect("Could not write to stdout");
+}
+
+#[cfg(not(unix))]
+fn writeNameValueLine(handle: &mut ::std::io::StdoutLock, name: &OsStr, value: &OsStr)
+{
+	writeln!(handle, "{}={}", name.to_string_lossy(), value.to_string_lossy()).expect("Could not write to stdout");
+}
+
 fn homeFolderIgnoringValueOfHomeVariable() -> PathBuf
 {
 	remove_var("HOME");
@@ -57,10 +143,9 @@ fn homeFolderIgnoringValueOfHomeVariable() -> PathBuf
 	}
 }
 
-fn settingsFor(programName: &OsStr, fileKind: &'static str) -> Option<PathBuf>
+fn settingsFor(settingsDirectory: &Path, programName: &OsStr, fileKind: &'static str) -> Option<PathBuf>
 {
-	let mut settingsFolderPath = homeFolderIgnoringValueOfHomeVariable();
-	settingsFolderPath = settingsFolderPath.join(".environment-sanity/settings");
+	let mut settingsFolderPath = settingsDirectory.to_path_buf();
 	settingsFolderPath = settingsFolderPath.join(PathBuf::from(programName));
 	settingsFolderPath = settingsFolderPath.join(PathBuf::from(fileKind.to_lowercase()));
 	if settingsFolderPath.exists() && settingsFolderPath.is_file()
@@ -73,6 +158,108 @@ fn settingsFor(programName: &OsStr, fileKind: &'static str) -> Option<PathBuf>
 	}
 }
 
+fn profilesFor(settingsDirectory: &Path, programName: &OsStr) -> Vec<Profile>
+{
+	match settingsFor(settingsDirectory, programName, "Profiles")
+	{
+		None => Vec::new(),
+		Some(filePath) =>
+		{
+			rawLinesListedInFile("Profiles", &filePath)
+			.into_iter()
+			.filter(|rawProfileName| !rawProfileName.is_empty())
+			.map(|rawProfileName| match Profile::parse(rawProfileName.as_slice())
+			{
+				Some(profile) => profile,
+				None => fatalExit!("Unknown profile '{:?}' listed in profiles file '{:?}'", String::from_utf8_lossy(&rawProfileName), filePath),
+			})
+			.collect()
+		}
+	}
+}
+
+/// A named, reusable bundle of blacklist/whitelist/settings entries that a program can opt into via its `profiles` settings file, instead of maintaining the same whitelist entries by hand in every program's `white` file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile
+{
+	Ssh,
+	Interactive,
+	LocalePassthrough,
+}
+
+impl Profile
+{
+	fn parse(rawProfileName: &[u8]) -> Option<Self>
+	{
+		match rawProfileName
+		{
+			b"ssh" => Some(Profile::Ssh),
+			b"interactive" => Some(Profile::Interactive),
+			b"locale-passthrough" => Some(Profile::LocalePassthrough),
+			_ => None,
+		}
+	}
+
+	fn blackList(&self) -> Vec<EnvironmentVariable>
+	{
+		Vec::new()
+	}
+
+	fn whiteList(&self) -> Vec<EnvironmentVariable>
+	{
+		match *self
+		{
+			Profile::Ssh => vec!
+			[
+				"SSH_CONNECTION".into(),
+				"SSH_AUTH_SOCK".into(),
+			],
+
+			Profile::Interactive => vec!
+			[
+				"TERM".into(),
+				"COLUMNS".into(),
+				"LINES".into(),
+				"DISPLAY".into(),
+				"EDITOR".into(),
+				"VISUAL".into(),
+			],
+
+			Profile::LocalePassthrough => localeEnvironmentVariables(),
+		}
+	}
+
+	fn settings(&self) -> HashMap<EnvironmentVariable, OsString>
+	{
+		HashMap::new()
+	}
+
+	/// Names that must be removed from the compiled-in forced defaults so that whitelisting them (via `whiteList()`) actually lets the incoming value through
+	fn settingsToRemove(&self) -> Vec<EnvironmentVariable>
+	{
+		match *self
+		{
+			Profile::LocalePassthrough => localeEnvironmentVariables(),
+			_ => Vec::new(),
+		}
+	}
+}
+
+fn localeEnvironmentVariables() -> Vec<EnvironmentVariable>
+{
+	vec!
+	[
+		"LC_ALL".into(),
+		"LC_COLLATE".into(),
+		"LC_CTYPE".into(),
+		"LC_MESSAGES".into(),
+		"LC_MONETARY".into(),
+		"LC_NUMERIC".into(),
+		"LC_TIME".into(),
+		"LANG".into(),
+	]
+}
+
 fn defaultBlackList() -> Vec<EnvironmentVariable>
 {
 	vec!
@@ -127,6 +314,14 @@ fn defaultBlackList() -> Vec<EnvironmentVariable>
 	]
 }
 
+fn defaultPathListSanitizer() -> Vec<EnvironmentVariable>
+{
+	vec!
+	[
+		"PATH".into(),
+	]
+}
+
 fn defaultWhiteList() -> Vec<EnvironmentVariable>
 {
 	vec!
@@ -135,21 +330,15 @@ fn defaultWhiteList() -> Vec<EnvironmentVariable>
 		"TMPDIR".into(), // We should consider using a path under the user's home instead; Rust's std::env::temp_dir() defaults even to a non-extant '/tmp'!
 		
 		// PWD - without this, musl's implementation of get_current_dir_name() falls back to getcwd()
-		
-		//"SSH_CONNECTION".into(),
-		//"SSH_AUTH_SOCK".into(),
-		
+
 		//"NLSPATH".into(),
 		//"DATEMSK".into(),
 		//"MSGVERB".into(),
 		// MUSL_LOCPATH
-		
-		//"TERM".into(),
-		//"COLUMNS".into(),
-		//"LINES".into(),
-		//"DISPLAY".into(),
-		//"EDITOR".into(),
-		//"VISUAL".into(),
+
+		// SSH_CONNECTION, SSH_AUTH_SOCK: see the "ssh" profile
+		// TERM, COLUMNS, LINES, DISPLAY, EDITOR, VISUAL: see the "interactive" profile
+		// LC_ALL, LC_COLLATE, LC_CTYPE, LC_MESSAGES, LC_MONETARY, LC_NUMERIC, LC_TIME, LANG: see the "locale-passthrough" profile
 	]
 }
 